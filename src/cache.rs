@@ -0,0 +1,206 @@
+/// On-disk cache of parsed calibration records, so that repeated `findall()` calls don't
+/// have to pay for a slow, permission-gated HID read of the EEPROM every time.
+use crate::{is_supported_config_version, LookingGlass};
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+// Each cached field is tag(1B) + length(4B BE) + payload, so future fields can be added
+// without breaking caches written by an older crate version (unknown tags are skipped).
+const TAG_SERIAL: u8 = 1;
+const TAG_CONFIG_VERSION: u8 = 2;
+const TAG_PITCH: u8 = 3;
+const TAG_SLOPE: u8 = 4;
+const TAG_CENTER: u8 = 5;
+const TAG_VIEW_CONE: u8 = 6;
+const TAG_INV_VIEW: u8 = 7;
+const TAG_VERTICAL_ANGLE: u8 = 8;
+const TAG_DPI: u8 = 9;
+const TAG_SCREEN_W: u8 = 10;
+const TAG_SCREEN_H: u8 = 11;
+const TAG_FLIP_IMAGE_X: u8 = 12;
+const TAG_FLIP_IMAGE_Y: u8 = 13;
+const TAG_FLIP_SUBP: u8 = 14;
+const TAG_HID_PATH: u8 = 15;
+
+fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_CACHE_HOME") {
+        return Some(PathBuf::from(dir).join("pluton"));
+    }
+
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache").join("pluton"))
+}
+
+// The HID-reported serial isn't guaranteed unique, so the cache is keyed on the HID
+// device path instead (the same path `LookingGlass::open()` already treats as stable
+// enough to reconnect on). Hex-encode it since paths can contain characters (`/`, `\`)
+// that aren't safe to use directly as a single path component.
+fn cache_path(hid_path: &CStr) -> Option<PathBuf> {
+    let encoded: String = hid_path.to_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+    cache_dir().map(|dir| dir.join(format!("{}.plgcache", encoded)))
+}
+
+fn write_field(buf: &mut Vec<u8>, tag: u8, payload: &[u8]) {
+    buf.push(tag);
+    buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buf.extend_from_slice(payload);
+}
+
+fn encode(glass: &LookingGlass) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    write_field(&mut buf, TAG_SERIAL, glass.serial.as_bytes());
+    write_field(&mut buf, TAG_CONFIG_VERSION, glass.config_version.as_bytes());
+    write_field(&mut buf, TAG_PITCH, &glass.pitch.to_be_bytes());
+    write_field(&mut buf, TAG_SLOPE, &glass.slope.to_be_bytes());
+    write_field(&mut buf, TAG_CENTER, &glass.center.to_be_bytes());
+    write_field(&mut buf, TAG_VIEW_CONE, &glass.view_cone.to_be_bytes());
+    write_field(&mut buf, TAG_INV_VIEW, &glass.inv_view.to_be_bytes());
+    write_field(&mut buf, TAG_VERTICAL_ANGLE, &glass.vertical_angle.to_be_bytes());
+    write_field(&mut buf, TAG_DPI, &glass.dpi.to_be_bytes());
+    write_field(&mut buf, TAG_SCREEN_W, &glass.screen_w.to_be_bytes());
+    write_field(&mut buf, TAG_SCREEN_H, &glass.screen_h.to_be_bytes());
+    write_field(&mut buf, TAG_FLIP_IMAGE_X, &glass.flip_image_x.to_be_bytes());
+    write_field(&mut buf, TAG_FLIP_IMAGE_Y, &glass.flip_image_y.to_be_bytes());
+    write_field(&mut buf, TAG_FLIP_SUBP, &glass.flip_subp.to_be_bytes());
+    write_field(&mut buf, TAG_HID_PATH, glass.hid_path.as_bytes());
+
+    buf
+}
+
+fn decode(bytes: &[u8]) -> Option<LookingGlass> {
+    let mut fields: HashMap<u8, &[u8]> = HashMap::new();
+    let mut cursor = 0;
+
+    while cursor + 5 <= bytes.len() {
+        let tag = bytes[cursor];
+        let len = u32::from_be_bytes(bytes[cursor + 1..cursor + 5].try_into().ok()?) as usize;
+        cursor += 5;
+
+        if cursor + len > bytes.len() {
+            return None;
+        }
+
+        fields.insert(tag, &bytes[cursor..cursor + len]);
+        cursor += len;
+    }
+
+    let get_str = |tag: u8| -> Option<String> {
+        String::from_utf8(fields.get(&tag)?.to_vec()).ok()
+    };
+    let get_f32 = |tag: u8| -> Option<f32> {
+        Some(f32::from_be_bytes((*fields.get(&tag)?).try_into().ok()?))
+    };
+    let get_u32 = |tag: u8| -> Option<u32> {
+        Some(u32::from_be_bytes((*fields.get(&tag)?).try_into().ok()?))
+    };
+
+    let config_version = get_str(TAG_CONFIG_VERSION)?;
+    if !is_supported_config_version(&config_version) {
+        return None;
+    }
+
+    Some(LookingGlass {
+        serial: get_str(TAG_SERIAL)?,
+        pitch: get_f32(TAG_PITCH)?,
+        slope: get_f32(TAG_SLOPE)?,
+        center: get_f32(TAG_CENTER)?,
+        view_cone: get_f32(TAG_VIEW_CONE)?,
+        inv_view: get_f32(TAG_INV_VIEW)?,
+        vertical_angle: get_f32(TAG_VERTICAL_ANGLE)?,
+        dpi: get_f32(TAG_DPI)?,
+        screen_w: get_u32(TAG_SCREEN_W)?,
+        screen_h: get_u32(TAG_SCREEN_H)?,
+        flip_image_x: get_f32(TAG_FLIP_IMAGE_X)?,
+        flip_image_y: get_f32(TAG_FLIP_IMAGE_Y)?,
+        flip_subp: get_f32(TAG_FLIP_SUBP)?,
+        hid_path: CString::new(fields.get(&TAG_HID_PATH)?.to_vec()).ok()?,
+        config_version,
+    })
+}
+
+/// Loads a cached record for `hid_path`, if present, parseable, and not from an
+/// unsupported (e.g. stale) configVersion.  All failure modes just return `None`, since a
+/// cache is an optimization and callers always have the HID read path to fall back on.
+pub(crate) fn load(hid_path: &CStr) -> Option<LookingGlass> {
+    let path = cache_path(hid_path)?;
+    let mut bytes = Vec::new();
+    fs::File::open(path).ok()?.read_to_end(&mut bytes).ok()?;
+    decode(&bytes)
+}
+
+/// Writes `glass` to the disk cache under `hid_path`, best-effort: any io error (missing
+/// permissions, read-only filesystem, etc) is silently ignored.
+pub(crate) fn store(hid_path: &CStr, glass: &LookingGlass) {
+    let path = match cache_path(hid_path) {
+        Some(path) => path,
+        None => return,
+    };
+
+    if let Some(parent) = path.parent() {
+        if fs::create_dir_all(parent).is_err() {
+            return;
+        }
+    }
+
+    if let Ok(mut file) = fs::File::create(path) {
+        let _ = file.write_all(&encode(glass));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_glass() -> LookingGlass {
+        LookingGlass {
+            serial: "00297".to_string(),
+            pitch: 49.81804275512695,
+            slope: 5.044347763061523,
+            center: 0.176902174949646,
+            view_cone: 40.0,
+            inv_view: 1.0,
+            vertical_angle: 0.0,
+            dpi: 338.0,
+            screen_w: 2560,
+            screen_h: 1600,
+            flip_image_x: 0.0,
+            flip_image_y: 0.0,
+            flip_subp: 0.0,
+            hid_path: CString::new("/dev/hidraw0").unwrap(),
+            config_version: "1.0".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let glass = test_glass();
+        let decoded = decode(&encode(&glass)).unwrap();
+
+        assert_eq!(decoded.serial, glass.serial);
+        assert_eq!(decoded.pitch, glass.pitch);
+        assert_eq!(decoded.screen_w, glass.screen_w);
+        assert_eq!(decoded.hid_path, glass.hid_path);
+        assert_eq!(decoded.config_version, glass.config_version);
+    }
+
+    #[test]
+    fn test_decode_rejects_unsupported_config_version() {
+        let mut glass = test_glass();
+        glass.config_version = "99.0".to_string();
+
+        assert!(decode(&encode(&glass)).is_none());
+    }
+
+    #[test]
+    fn test_decode_rejects_truncated_data() {
+        let mut bytes = encode(&test_glass());
+        bytes.truncate(bytes.len() - 1);
+
+        assert!(decode(&bytes).is_none());
+    }
+}
@@ -2,34 +2,78 @@
 /// Ian Rees 2019
 
 use bytes::{Bytes, BytesMut, BufMut};
-use hid;
+use hidapi;
 #[macro_use] extern crate serde_derive;
 use serde_json;
 
-use std::{error, fmt, str, time};
+use std::ffi::CString;
+use std::{error, fmt, str};
+
+mod cache;
 
 const LOOKING_GLASS_VID:u16 = 0x04D8;
 const LOOKING_GLASS_PID:u16 = 0xEf7E;
 
+/// Largest calibration blob we're willing to allocate for, in bytes.  Real
+/// calibration JSON is well under 1 KiB; this just needs to be comfortably
+/// larger than that while still bounding a garbage or hostile `json_size`.
+const MAX_CONFIG_SIZE: usize = 64 * 1024;
+
 pub struct LookingGlass {
     /// Serial number as reported from EEPROM, not HID.  Seems to be the "real one"
     pub serial: String,
     pub pitch: f32,
     pub slope: f32,
     pub center: f32,
+    pub view_cone: f32,
+    pub inv_view: f32,
+    pub vertical_angle: f32,
     pub dpi: f32,
     pub screen_w: u32, // Width in pixels
     pub screen_h: u32, // Height in pixels
+    pub flip_image_x: f32,
+    pub flip_image_y: f32,
+    pub flip_subp: f32,
+    /// HID device path, kept so `open()` can reconnect without re-enumerating
+    pub(crate) hid_path: CString,
+    /// configVersion this record was parsed from, kept so `write_config` knows
+    /// which schema to serialize back into, and so a cached record can be
+    /// recognized as stale if it's no longer a version we understand
+    pub(crate) config_version: String,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Error {
     HIDError(String),
-    ParseError(String)
+    ParseError(String),
+    /// A config is larger than `MAX_CONFIG_SIZE`: either the `json_size` the device
+    /// reported, or the JSON `write_config` serialized
+    ConfigTooLarge(String),
+    /// A `try_reserve` for a read, write or parse buffer failed
+    AllocError(String),
+    /// A page read returned no new bytes, so the paging loop would spin forever
+    TransferStalled(String),
+    /// A page written to the EEPROM didn't read back the way it was written
+    VerifyError(String),
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
+impl From<hidapi::HidError> for Error {
+    fn from(error: hidapi::HidError) -> Error {
+        Error::HIDError(error.to_string())
+    }
+}
+
+/// Reserves a `BytesMut` with exactly `capacity` bytes of backing storage,
+/// reporting an `Error::AllocError` instead of aborting if the reservation
+/// can't be made.
+fn try_bytes_mut_with_capacity(capacity: usize) -> Result<BytesMut> {
+    let mut buf = Vec::<u8>::new();
+    buf.try_reserve_exact(capacity).map_err(|error| Error::AllocError(error.to_string()))?;
+    Ok(BytesMut::from(buf))
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write_str(error::Error::description(self))
@@ -41,6 +85,33 @@ impl error::Error for Error {
         match *self {
             Error::HIDError(ref err) => err,
             Error::ParseError(ref err) => err,
+            Error::ConfigTooLarge(ref err) => err,
+            Error::AllocError(ref err) => err,
+            Error::TransferStalled(ref err) => err,
+            Error::VerifyError(ref err) => err,
+        }
+    }
+}
+
+/// An open connection to a Looking Glass, obtained from `LookingGlass::open()` without
+/// going back through `findall()`.  It is used to reconfigure the device through
+/// `LookingGlass::write_config()`; it has no public methods for reading pages or polling.
+pub struct Handle {
+    device: hidapi::HidDevice,
+}
+
+impl Handle {
+    fn from_device(device: hidapi::HidDevice) -> Result<Handle> {
+        device.set_blocking_mode(true)?;
+        Ok(Handle { device })
+    }
+
+    /// Surfaces the real hidapi error string for a failed feature-report send or
+    /// read, falling back to `fallback` if the device has nothing more specific to say.
+    fn error_detail(&self, fallback: hidapi::HidError) -> Error {
+        match self.device.check_error() {
+            Ok(detail) => Error::HIDError(detail.to_string()),
+            Err(_) => Error::HIDError(fallback.to_string()),
         }
     }
 }
@@ -50,108 +121,304 @@ impl LookingGlass {
     pub fn findall() -> Vec<Result<LookingGlass>> {
         let mut glasses = Vec::new();
 
-        let hid_manager = match hid::init() {
-            Ok(manager) => manager,
+        let api = match hidapi::HidApi::new() {
+            Ok(api) => api,
             Err(error) => {
                 glasses.push(Err(Error::HIDError(error.to_string())));
                 return glasses;
             }
         };
 
-        for candidate in hid_manager.find(Some(LOOKING_GLASS_VID), Some(LOOKING_GLASS_PID)) {
-            glasses.push(
-                if let Some(_hid_serial) = candidate.serial_number() {
-                    // Don't actually care about the serial reported to HID, as it is not unique
+        for info in api.device_list() {
+            if info.vendor_id() != LOOKING_GLASS_VID || info.product_id() != LOOKING_GLASS_PID {
+                continue;
+            }
 
-                    match get_json_string(candidate) {
-                        Ok(string) => json_to_glass(string),
-                        Err(error) => Err(Error::HIDError(error.to_string()))
+            // Keyed on the HID device path, not the HID-reported serial: the serial isn't
+            // guaranteed unique, but the path is the same thing `open()` already treats as
+            // stable enough to reconnect to this exact device.
+            let hid_path = info.path();
+
+            glasses.push(
+                match LookingGlass::load_cached(hid_path) {
+                    Some(cached) => Ok(cached),
+                    None => {
+                        let parsed = info.open_device(&api)
+                            .map_err(Error::from)
+                            .and_then(Handle::from_device)
+                            .and_then(|handle| get_json_string(&handle))
+                            .and_then(|json| json_to_glass(json, hid_path.to_owned()));
+
+                        if let Ok(ref glass) = parsed {
+                            cache::store(hid_path, glass);
+                        }
+
+                        parsed
                     }
-                } else {
-                    Err(Error::HIDError("Error reading - may lack permissions?".to_string()))
                 }
             );
         }
 
         glasses
     }
-}
 
-/// Parses JSON config string and instantiates a LookingGlass as appropriate
-fn json_to_glass(json_string: String) -> Result<LookingGlass>
-{
-    #[derive(Serialize, Deserialize)]
-    struct JSONValueMap {
-        value: f32
+    /// Re-opens this Looking Glass by its HID path, without re-enumerating all devices
+    pub fn open(&self) -> Result<Handle> {
+        let api = hidapi::HidApi::new()?;
+        let device = api.open_path(&self.hid_path)?;
+        Handle::from_device(device)
     }
 
-    #[derive(Serialize, Deserialize)]
-    #[allow(non_snake_case)]
-    struct ConfigJSON {
-        configVersion: String,
-        serial: String,
-        pitch: JSONValueMap,
-        slope: JSONValueMap,
-        center: JSONValueMap,
-        viewCone: JSONValueMap,
-        invView: JSONValueMap,
-        verticalAngle: JSONValueMap,
-        DPI: JSONValueMap,
-        screenW: JSONValueMap,
-        screenH: JSONValueMap,
-        flipImageX: JSONValueMap,
-        flipImageY: JSONValueMap,
-        flipSubp: JSONValueMap,
-    }
-
-    match serde_json::from_str::<ConfigJSON>(&json_string) {
-        Ok(config) => {
-            if config.configVersion == "1.0" {
-                Ok(LookingGlass {
-                    serial: config.serial,
-                    pitch: config.pitch.value,
-                    slope: config.slope.value,
-                    center: config.center.value,
-                    dpi: config.DPI.value,
-                    screen_w: config.screenW.value as u32,
-                    screen_h: config.screenH.value as u32,
-                })
+    /// Loads a cached calibration record previously stored under `hid_path` by
+    /// `findall()`.  Returns `None` on a cache miss, a corrupt entry, or a cached
+    /// `configVersion` this crate no longer understands — any of which just mean the
+    /// caller should fall back to reading the EEPROM over HID.
+    pub fn load_cached(hid_path: &std::ffi::CStr) -> Option<LookingGlass> {
+        cache::load(hid_path)
+    }
+
+    /// Reprograms a device's calibration, by serializing `glass` back into the device's
+    /// 64-byte paged layout (a 4-byte big-endian length prefix followed by that many bytes
+    /// of JSON) and writing it via feature reports, verifying each page as it's written
+    pub fn write_config(handle: &mut Handle, glass: &LookingGlass) -> Result<()> {
+        let json = glass_to_json(glass)?;
+        let json_bytes = json.as_bytes();
+
+        if json_bytes.len() > MAX_CONFIG_SIZE {
+            return Err(Error::ConfigTooLarge(format!(
+                "Serialized config is {} bytes, over the {}-byte limit",
+                json_bytes.len(), MAX_CONFIG_SIZE)));
+        }
+
+        for (addr, chunk) in paginate_json(json_bytes) {
+            if addr == 0 {
+                let mut page0 = try_bytes_mut_with_capacity(64)?;
+                page0.put_u32_be(json_bytes.len() as u32);
+                page0.extend_from_slice(chunk);
+                write_page(handle, 0, &page0)?;
             } else {
-                Err(Error::ParseError(format!("Don't know how to read config version {}...",
-                    config.configVersion)))
+                write_page(handle, addr, chunk)?;
             }
-        },
-        Err(error) => {
-            Err(Error::ParseError(format!("Error parsing JSON: {}", error.to_string())))
         }
+
+        Ok(())
     }
+
+    /// Computes which view of a `num_views`-view quilt the physical subpixel at pixel
+    /// coordinates `x`/`y` (each expected in `[0, screen_w)`/`[0, screen_h)`, giving
+    /// normalized `u`/`v` in `[0,1)`) and color channel `channel` (`0..3`, R/G/B) should
+    /// sample from, so a shader knows which quilt tile to read for that subpixel.
+    pub fn view_for_subpixel(&self, x: u32, y: u32, channel: u32, num_views: u32) -> u32 {
+        let (pitch_adj, tilt) = self.pitch_adj_and_tilt();
+        self.view_for_subpixel_with_geometry(x, y, channel, num_views, pitch_adj, tilt)
+    }
+
+    /// The parts of `view_for_subpixel`'s math that depend only on the glass's fixed
+    /// calibration, not on the subpixel being queried — hoisted out so `view_lookup_table`
+    /// can compute them once instead of once per subpixel.
+    fn pitch_adj_and_tilt(&self) -> (f32, f32) {
+        let mut pitch_adj = self.pitch * (self.screen_w as f32 / self.dpi) * (1.0 / self.slope).atan().cos();
+        if self.flip_image_x != 0.0 {
+            pitch_adj = -pitch_adj;
+        }
+
+        let mut tilt = self.screen_h as f32 / (self.screen_w as f32 * self.slope);
+        if self.flip_image_y != 0.0 {
+            tilt = -tilt;
+        }
+
+        (pitch_adj, tilt)
+    }
+
+    /// Core of `view_for_subpixel`, taking the calibration-derived `pitch_adj`/`tilt` as
+    /// precomputed inputs rather than recomputing them (which involves an `atan`+`cos`) on
+    /// every call.
+    fn view_for_subpixel_with_geometry(
+        &self, x: u32, y: u32, channel: u32, num_views: u32, pitch_adj: f32, tilt: f32,
+    ) -> u32 {
+        let u = x as f32 / self.screen_w as f32;
+        let v = y as f32 / self.screen_h as f32;
+
+        // `channel` is documented as 0..3; if a caller passes something outside that range,
+        // wrap (the same value a release build's overflowing subtraction gives) rather than
+        // panic as a debug build would.
+        let channel = if self.flip_subp != 0.0 { 2u32.wrapping_sub(channel) } else { channel };
+        let sp = (channel as f32) * (1.0 / 3.0) / (self.screen_w as f32);
+
+        let mut z = (u + sp + v * tilt) * pitch_adj - self.center;
+        z -= z.floor();
+        if self.inv_view != 0.0 {
+            z = 1.0 - z;
+        }
+
+        let view = (z * num_views as f32).floor() as i32;
+        view.max(0).min(num_views as i32 - 1) as u32
+    }
+
+    /// Batched `view_for_subpixel`, producing a full-screen lookup table indexed
+    /// `[(y * screen_w + x) * 3 + channel]`
+    pub fn view_lookup_table(&self, num_views: u32) -> Vec<u32> {
+        let mut table = Vec::with_capacity((self.screen_w * self.screen_h * 3) as usize);
+        let (pitch_adj, tilt) = self.pitch_adj_and_tilt();
+
+        for y in 0..self.screen_h {
+            for x in 0..self.screen_w {
+                for channel in 0..3 {
+                    table.push(self.view_for_subpixel_with_geometry(x, y, channel, num_views, pitch_adj, tilt));
+                }
+            }
+        }
+
+        table
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+struct JSONValueMap {
+    value: f32
+}
+
+/// Implemented by each known on-device `configVersion` schema, to normalize
+/// that version's fields into the common `LookingGlass` record and back.
+trait ConfigSchema {
+    fn into_glass(self, hid_path: CString) -> LookingGlass;
+    fn from_glass(glass: &LookingGlass) -> Self;
+}
+
+/// Schema for `configVersion == "1.0"`.  Keys it doesn't know are ignored, so
+/// firmware which quietly adds keys under the same version string still parses.
+/// `viewCone`, `invView`, `verticalAngle` and the three flip fields default to 0
+/// when absent; `serial`, `pitch`, `slope`, `center`, `DPI`, `screenW` and
+/// `screenH` are required.
+#[derive(Serialize, Deserialize)]
+#[allow(non_snake_case)]
+struct ConfigV1_0 {
+    configVersion: String,
+    serial: String,
+    pitch: JSONValueMap,
+    slope: JSONValueMap,
+    center: JSONValueMap,
+    #[serde(default)]
+    viewCone: JSONValueMap,
+    #[serde(default)]
+    invView: JSONValueMap,
+    #[serde(default)]
+    verticalAngle: JSONValueMap,
+    DPI: JSONValueMap,
+    screenW: JSONValueMap,
+    screenH: JSONValueMap,
+    #[serde(default)]
+    flipImageX: JSONValueMap,
+    #[serde(default)]
+    flipImageY: JSONValueMap,
+    #[serde(default)]
+    flipSubp: JSONValueMap,
+}
+
+impl ConfigSchema for ConfigV1_0 {
+    fn into_glass(self, hid_path: CString) -> LookingGlass {
+        LookingGlass {
+            serial: self.serial,
+            pitch: self.pitch.value,
+            slope: self.slope.value,
+            center: self.center.value,
+            view_cone: self.viewCone.value,
+            inv_view: self.invView.value,
+            vertical_angle: self.verticalAngle.value,
+            dpi: self.DPI.value,
+            screen_w: self.screenW.value as u32,
+            screen_h: self.screenH.value as u32,
+            flip_image_x: self.flipImageX.value,
+            flip_image_y: self.flipImageY.value,
+            flip_subp: self.flipSubp.value,
+            hid_path,
+            config_version: self.configVersion,
+        }
+    }
+
+    fn from_glass(glass: &LookingGlass) -> ConfigV1_0 {
+        ConfigV1_0 {
+            configVersion: glass.config_version.clone(),
+            serial: glass.serial.clone(),
+            pitch: JSONValueMap { value: glass.pitch },
+            slope: JSONValueMap { value: glass.slope },
+            center: JSONValueMap { value: glass.center },
+            viewCone: JSONValueMap { value: glass.view_cone },
+            invView: JSONValueMap { value: glass.inv_view },
+            verticalAngle: JSONValueMap { value: glass.vertical_angle },
+            DPI: JSONValueMap { value: glass.dpi },
+            screenW: JSONValueMap { value: glass.screen_w as f32 },
+            screenH: JSONValueMap { value: glass.screen_h as f32 },
+            flipImageX: JSONValueMap { value: glass.flip_image_x },
+            flipImageY: JSONValueMap { value: glass.flip_image_y },
+            flipSubp: JSONValueMap { value: glass.flip_subp },
+        }
+    }
+}
+
+/// Parses JSON config string and instantiates a LookingGlass as appropriate
+fn json_to_glass(json_string: String, hid_path: CString) -> Result<LookingGlass>
+{
+    #[derive(Deserialize)]
+    #[allow(non_snake_case)]
+    struct VersionProbe {
+        configVersion: String,
+    }
+
+    let probe = serde_json::from_str::<VersionProbe>(&json_string)
+        .map_err(|error| Error::ParseError(format!("Error parsing JSON: {}", error.to_string())))?;
+
+    // Version-dispatch layer: each known configVersion gets its own schema
+    // struct above, which is then normalized into a LookingGlass.
+    match probe.configVersion.as_str() {
+        "1.0" => serde_json::from_str::<ConfigV1_0>(&json_string)
+            .map(|config| config.into_glass(hid_path))
+            .map_err(|error| Error::ParseError(format!("Error parsing JSON: {}", error.to_string()))),
+        other => Err(Error::ParseError(format!("Don't know how to read config version {}...", other))),
+    }
+}
+
+/// Serializes a LookingGlass back into the JSON it was parsed from, using the
+/// schema matching its `config_version`
+fn glass_to_json(glass: &LookingGlass) -> Result<String> {
+    match glass.config_version.as_str() {
+        "1.0" => serde_json::to_string(&ConfigV1_0::from_glass(glass))
+            .map_err(|error| Error::ParseError(format!("Error serializing JSON: {}", error.to_string()))),
+        other => Err(Error::ParseError(format!("Don't know how to write config version {}...", other))),
+    }
+}
+
+/// True if `version` is a `configVersion` we have a schema for.  Used to recognize a disk
+/// cache entry from an older crate version as stale, rather than trusting it blindly.
+pub(crate) fn is_supported_config_version(version: &str) -> bool {
+    version == "1.0"
 }
 
 /// For whatever reason, we only get 64-byte results from read(), but device reports 68 per page...
-fn hid_multiread(handle: &mut hid::Handle) -> hid::Result<BytesMut> {
-    let mut ret_buf = BytesMut::with_capacity(0);
+fn hid_multiread(handle: &Handle) -> Result<BytesMut> {
+    let mut ret_buf = try_bytes_mut_with_capacity(0)?;
     loop {
         // On Ubuntu 18.04, we'll either need 64 or 68 bytes, apparently (based on Python
         // experiments) depending on whether libhidapi uses libusb or libhidraw.
-        let mut this_read = BytesMut::with_capacity(128);
+        let mut this_read = try_bytes_mut_with_capacity(128)?;
         this_read.resize(128, 0);
 
         // Magic number warning: the read timeout is just a guess
-        match handle.data().read(&mut this_read, time::Duration::from_millis(10))? {
-            Some(count) => {
-                ret_buf.extend_from_slice(&this_read[..count]);
-            },
-            None => {
-                break;
-            },
+        let count = handle.device.read_timeout(&mut this_read, 10)
+            .map_err(|error| handle.error_detail(error))?;
+
+        if count == 0 {
+            break;
         }
+
+        ret_buf.extend_from_slice(&this_read[..count]);
     }
     Ok(ret_buf)
 }
 
 /// Does a single write/read transaction to read a page of data from the LG's EEPROM
-fn hid_query(handle: &mut hid::Handle, addr:u16) -> hid::Result<BytesMut> {
-    let mut buf = BytesMut::with_capacity(512);
+fn hid_query(handle: &Handle, addr:u16) -> Result<BytesMut> {
+    let mut buf = try_bytes_mut_with_capacity(512)?;
 
     // Flush the read buffer
     hid_multiread(handle)?;
@@ -160,10 +427,7 @@ fn hid_query(handle: &mut hid::Handle, addr:u16) -> hid::Result<BytesMut> {
     buf.put_u16_be(addr);
     buf.resize(68, 0); // Looking Glass needs a 68-Byte request, unclear why that is
 
-    let count = handle.feature().send(&buf)?;
-    if count != buf.len() {
-        return Err(hid::Error::Write);
-    }
+    handle.device.send_feature_report(&buf).map_err(|error| handle.error_detail(error))?;
 
     buf = hid_multiread(handle)?;
 
@@ -173,21 +437,53 @@ fn hid_query(handle: &mut hid::Handle, addr:u16) -> hid::Result<BytesMut> {
     if buf.len() <= 4 ||
        buf.split_to(4) != Bytes::from(&confirm[..]) {
         println!("Confirm failed!");
-        return Err(hid::Error::Read);
+        return Err(Error::HIDError("Confirm failed".to_string()));
     }
 
     Ok(buf)
 }
 
-/// Extracts the JSON-formatted configuration string from LG's EEPROM via HID
-fn get_json_string(candidate: hid::Device) -> hid::Result<String> {
-    let mut handle = candidate.open()?;
+/// Splits a serialized config into the (addr, data) pages `write_config` writes: page 0's
+/// data is the first up-to-60 bytes of JSON (the remaining 4 of its 64 bytes hold the
+/// length prefix `write_config` prepends), and later pages are 64 bytes of JSON each,
+/// mirroring the layout `get_json_string` reads back.
+fn paginate_json(json_bytes: &[u8]) -> Vec<(u16, &[u8])> {
+    let (first_chunk, rest) = json_bytes.split_at(json_bytes.len().min(60));
+    let mut pages = vec![(0u16, first_chunk)];
+
+    for (index, chunk) in rest.chunks(64).enumerate() {
+        pages.push(((index + 1) as u16, chunk));
+    }
+
+    pages
+}
+
+/// Writes a single 64-byte page to the LG's EEPROM, mirroring the request framing
+/// `hid_query` uses to read one, then reads the page back to verify it took
+fn write_page(handle: &Handle, addr: u16, page_data: &[u8]) -> Result<()> {
+    let mut buf = try_bytes_mut_with_capacity(68)?;
+
+    buf.put_u16_be(0); // First byte of this is HID "report ID"
+    buf.put_u16_be(addr);
+    buf.extend_from_slice(page_data);
+    buf.resize(68, 0); // Looking Glass needs a 68-Byte request, unclear why that is
+
+    handle.device.send_feature_report(&buf).map_err(|error| handle.error_detail(error))?;
+
+    // Don't trust the device to report back a page at least as long as what we wrote.
+    let readback = hid_query(handle, addr)?;
+    if readback.len() < page_data.len() || &readback[..page_data.len()] != page_data {
+        return Err(Error::VerifyError(format!("Page {} didn't read back as written", addr)));
+    }
 
-    handle.blocking(true)?;
+    Ok(())
+}
 
+/// Extracts the JSON-formatted configuration string from LG's EEPROM via HID
+fn get_json_string(handle: &Handle) -> Result<String> {
     // Data is organised in 64-byte pages.  Page 0 starts with 4B of length, followed by that many
     // bytes of JSON-formatted data.  First read will then have the length and some JSON data.
-    let json_size_raw = hid_query(&mut handle, 0)?;
+    let json_size_raw = hid_query(handle, 0)?;
 
     // Can't see how to nicely turn a BytesMut in to a Buf to use get_u32_be()...
     let mut json_size = 0usize;
@@ -196,13 +492,28 @@ fn get_json_string(candidate: hid::Device) -> hid::Result<String> {
         json_size += json_size_raw[i] as usize;
     }
 
+    // A garbage or hostile EEPROM could report a huge length; bail out before
+    // trusting it for allocation or turning the page loop below into a near-infinite read.
+    if json_size > MAX_CONFIG_SIZE {
+        return Err(Error::ConfigTooLarge(format!(
+            "Device reports a {}-byte config, which is over the {}-byte limit",
+            json_size, MAX_CONFIG_SIZE)));
+    }
+
     // Keep the remaining bytes from page 0
-    let mut json = Vec::from(&json_size_raw[4..]);
+    let mut json = Vec::new();
+    json.try_reserve_exact(json_size).map_err(|error| Error::AllocError(error.to_string()))?;
+    json.extend_from_slice(&json_size_raw[4..]);
 
     // Then, read the remaining pages
     while json.len() < json_size {
         let last_page = (json.len() / 64) as u16;
-        let this_read = hid_query(&mut handle, last_page + 1)?;
+        let this_read = hid_query(handle, last_page + 1)?;
+
+        if this_read.is_empty() {
+            return Err(Error::TransferStalled(format!(
+                "Page {} read returned no new bytes", last_page + 1)));
+        }
 
         json.extend_from_slice(&this_read);
     }
@@ -211,7 +522,7 @@ fn get_json_string(candidate: hid::Device) -> hid::Result<String> {
 
     match str::from_utf8(&json) {
         Ok(yay) => Ok(yay.to_string()),
-        Err(error) => Err(hid::Error::String(error.to_string()))
+        Err(error) => Err(Error::ParseError(error.to_string()))
     }
 }
 
@@ -260,20 +571,147 @@ mod tests {
             r#""flipImageX":{"value":0.0},"flipImageY":{"value":0.0},"flipSubp":{"value":0.0}}"#
             ).to_string();
 
-        match json_to_glass(json) {
+        match json_to_glass(json, CString::new("test").unwrap()) {
             Ok(glass) => {
                 // Not sure that comparing for equality with floats here is a great idea...
                 assert_eq!(glass.serial, "00297");
                 assert_eq!(glass.pitch, 49.81804275512695);
                 assert_eq!(glass.slope, 5.044347763061523);
                 assert_eq!(glass.center, 0.176902174949646);
+                assert_eq!(glass.view_cone, 40.0);
+                assert_eq!(glass.inv_view, 1.0);
+                assert_eq!(glass.vertical_angle, 0.0);
                 assert_eq!(glass.dpi, 338.0);
                 assert_eq!(glass.screen_w, 2560);
                 assert_eq!(glass.screen_h, 1600);
+                assert_eq!(glass.flip_image_x, 0.0);
+                assert_eq!(glass.flip_image_y, 0.0);
+                assert_eq!(glass.flip_subp, 0.0);
             },
             Err(..) => {
                 assert!(false);
             }
         }
     }
+
+    #[test]
+    fn test_json_to_glass_unknown_version() {
+        let json = r#"{"configVersion":"99.0","serial":"00297"}"#.to_string();
+
+        match json_to_glass(json, CString::new("test").unwrap()) {
+            Ok(..) => assert!(false),
+            Err(error) => assert_eq!(error, Error::ParseError(
+                "Don't know how to read config version 99.0...".to_string())),
+        }
+    }
+
+    #[test]
+    fn test_glass_to_json_round_trip() {
+        let json = concat!(
+            r#"{"configVersion":"1.0","serial":"00297","pitch":{"value":49.81804275512695},"#,
+            r#""slope":{"value":5.044347763061523},"center":{"value":0.176902174949646},"#,
+            r#""viewCone":{"value":40.0},"invView":{"value":1.0},"verticalAngle":{"value":0.0},"#,
+            r#""DPI":{"value":338.0},"screenW":{"value":2560.0},"screenH":{"value":1600.0},"#,
+            r#""flipImageX":{"value":0.0},"flipImageY":{"value":0.0},"flipSubp":{"value":0.0}}"#
+            ).to_string();
+
+        let glass = json_to_glass(json, CString::new("test").unwrap()).unwrap();
+        let serialized = glass_to_json(&glass).unwrap();
+        let round_tripped = json_to_glass(serialized, CString::new("test").unwrap()).unwrap();
+
+        assert_eq!(round_tripped.serial, glass.serial);
+        assert_eq!(round_tripped.pitch, glass.pitch);
+        assert_eq!(round_tripped.view_cone, glass.view_cone);
+        assert_eq!(round_tripped.screen_w, glass.screen_w);
+        assert_eq!(round_tripped.flip_subp, glass.flip_subp);
+    }
+
+    fn test_glass(flip_subp: f32) -> LookingGlass {
+        let json = format!(concat!(
+            r#"{{"configVersion":"1.0","serial":"00297","pitch":{{"value":49.81804275512695}},"#,
+            r#""slope":{{"value":5.044347763061523}},"center":{{"value":0.176902174949646}},"#,
+            r#""viewCone":{{"value":40.0}},"invView":{{"value":1.0}},"verticalAngle":{{"value":0.0}},"#,
+            r#""DPI":{{"value":338.0}},"screenW":{{"value":2560.0}},"screenH":{{"value":1600.0}},"#,
+            r#""flipImageX":{{"value":0.0}},"flipImageY":{{"value":0.0}},"flipSubp":{{"value":{}}}}}"#
+            ), flip_subp);
+
+        json_to_glass(json, CString::new("test").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_view_for_subpixel_in_bounds() {
+        let glass = test_glass(0.0);
+        let num_views = 45;
+
+        for y in (0..glass.screen_h).step_by(137) {
+            for x in (0..glass.screen_w).step_by(137) {
+                for channel in 0..3 {
+                    let view = glass.view_for_subpixel(x, y, channel, num_views);
+                    assert!(view < num_views);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_view_for_subpixel_flip_subp_reverses_channel_order() {
+        let glass = test_glass(0.0);
+        let flipped = test_glass(1.0);
+        let num_views = 45;
+
+        assert_eq!(glass.view_for_subpixel(123, 45, 0, num_views),
+                   flipped.view_for_subpixel(123, 45, 2, num_views));
+        assert_eq!(glass.view_for_subpixel(123, 45, 1, num_views),
+                   flipped.view_for_subpixel(123, 45, 1, num_views));
+    }
+
+    #[test]
+    fn test_view_for_subpixel_out_of_range_channel_does_not_panic() {
+        let glass = test_glass(1.0);
+        let num_views = 45;
+
+        let view = glass.view_for_subpixel(123, 45, 3, num_views);
+        assert!(view < num_views);
+    }
+
+    #[test]
+    fn test_view_lookup_table_matches_view_for_subpixel() {
+        let glass = test_glass(0.0);
+        let num_views = 45;
+
+        let table = glass.view_lookup_table(num_views);
+        assert_eq!(table.len(), (glass.screen_w * glass.screen_h * 3) as usize);
+
+        for (y, x, channel) in [(0, 0, 0), (10, 20, 1), (1599, 2559, 2)] {
+            let index = ((y * glass.screen_w + x) * 3 + channel) as usize;
+            assert_eq!(table[index], glass.view_for_subpixel(x, y, channel, num_views));
+        }
+    }
+
+    #[test]
+    fn test_paginate_json_short_fits_in_page0() {
+        let json = vec![0xAB; 40];
+        let pages = paginate_json(&json);
+
+        assert_eq!(pages, vec![(0, &json[..])]);
+    }
+
+    #[test]
+    fn test_paginate_json_splits_across_pages() {
+        let json = vec![0xAB; 200];
+        let pages = paginate_json(&json);
+
+        assert_eq!(pages.len(), 1 + (200 - 60 + 63) / 64);
+        assert_eq!(pages[0], (0, &json[..60]));
+        assert_eq!(pages[1], (1, &json[60..124]));
+        assert_eq!(pages[2], (2, &json[124..188]));
+        assert_eq!(pages[3], (3, &json[188..200]));
+    }
+
+    #[test]
+    fn test_paginate_json_empty() {
+        let pages = paginate_json(&[]);
+
+        assert_eq!(pages, vec![(0, &[][..])]);
+    }
 }